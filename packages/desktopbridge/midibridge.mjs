@@ -1,5 +1,9 @@
+import { listen } from '@tauri-apps/api/event';
 import { Invoke } from './utils.mjs';
-import { Pattern, getEventOffsetMs, noteToMidi } from '@strudel/core';
+import { Pattern, getEventOffsetMs, logger, noteToMidi } from '@strudel/core';
+
+// exposed in the repl scope, the latency applies to both midi and osc
+export { setLatency } from './utils.mjs';
 
 const ON_MESSAGE = 0x90;
 const OFF_MESSAGE = 0x80;
@@ -52,3 +56,70 @@ Pattern.prototype.midi = function (output) {
     }
   });
 };
+
+// tempo and transport derived from incoming midi clock,
+// payload is { message_type: 'start' | 'continue' | 'stop' | 'tempo', bpm }
+export const onMidiClock = (callback) =>
+  listen('midi-clock', (e) => {
+    if (e.payload == null) {
+      return;
+    }
+    callback(e.payload);
+  });
+
+let followedPort, unlistenMidiClock;
+
+const logClockError = (err) => logger(`[midi clock] ${err?.message ?? err}`, 'error');
+// repl.start throws when nothing has been evaluated yet
+const runTransport = (action) => Promise.resolve().then(action).catch(logClockError);
+
+/**
+ * Lets an external device sending midi clock drive the scheduler: start rewinds and plays,
+ * stop pauses, continue resumes, and the clock tempo sets the cps.
+ * Only the given input port is opened, and only once this is called, e.g. `followMidiClock('IAC')`.
+ * Call stopMidiClock() to close the port again.
+ *
+ * @param {string} port name or part of the name of the midi input, defaults to the first one
+ * @param {number} beatsPerCycle beats of the clock per cycle, 4 by default
+ */
+export async function followMidiClock(port = '', beatsPerCycle = 4, repl = window.strudelMirror?.repl) {
+  if (repl == null) {
+    logClockError('no repl to follow the clock with');
+    return;
+  }
+  // this runs again on every evaluate, keep the port that is already open
+  if (port === followedPort) {
+    return;
+  }
+  await stopMidiClock();
+  followedPort = port;
+  try {
+    await Invoke('listenmidiclock', { requestedport: port });
+  } catch (err) {
+    followedPort = undefined;
+    logClockError(err);
+    return;
+  }
+  unlistenMidiClock = await onMidiClock(({ message_type, bpm }) => {
+    if (message_type === 'start') {
+      runTransport(() => {
+        repl.stop();
+        return repl.start();
+      });
+    } else if (message_type === 'continue') {
+      runTransport(() => repl.start());
+    } else if (message_type === 'stop') {
+      runTransport(() => repl.pause());
+    } else if (message_type === 'tempo' && bpm > 0) {
+      repl.setCps(bpm / 60 / beatsPerCycle);
+    }
+  });
+}
+
+// closes the clock input port and stops following it
+export async function stopMidiClock() {
+  followedPort = undefined;
+  unlistenMidiClock?.();
+  unlistenMidiClock = undefined;
+  await Invoke('stopmidiclock').catch(logClockError);
+}
@@ -2,6 +2,9 @@ import { Pattern, ClockCollator } from '@strudel/core';
 import { parseControlsFromHap } from 'node_modules/@strudel/osc/osc.mjs';
 import { Invoke } from './utils.mjs';
 
+// exposed in the repl scope, the latency applies to both midi and osc
+export { setLatency } from './utils.mjs';
+
 const collator = new ClockCollator({});
 
 export async function oscTriggerTauri(hap, currentTime, cps = 1, targetTime) {
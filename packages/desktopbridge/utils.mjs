@@ -1,4 +1,8 @@
 import { invoke } from '@tauri-apps/api/core';
+import { logger } from '@strudel/core';
 
 export const Invoke = invoke;
 export const isTauri = () => window.__TAURI_IPC__ != null;
+// offset in seconds (-1 to 1) added to every midi and osc message sent by the backend
+export const setLatency = (seconds) =>
+  Invoke('setlatency', { seconds }).catch((err) => logger(`[desktopbridge] ${err}`, 'error'));
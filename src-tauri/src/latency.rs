@@ -0,0 +1,61 @@
+use tokio::sync::Mutex;
+
+// anything beyond this is a mistake rather than a latency compensation
+const MAX_LATENCY_SECONDS: f64 = 1.0;
+
+// global offset (seconds) added to every scheduled midi and osc message
+pub struct LatencyOffset {
+  pub inner: Mutex<f64>,
+}
+
+// midi offsets are in ms from now, negative latency can pull them to now at most
+pub fn apply_latency_ms(offset: u64, seconds: f64) -> u64 {
+  let latency_ms = (seconds * 1000.0).round() as i64;
+  if latency_ms >= 0 {
+    offset.saturating_add(latency_ms as u64)
+  } else {
+    offset.saturating_sub(latency_ms.unsigned_abs())
+  }
+}
+
+// osc timestamps are in seconds since the unix epoch
+pub fn apply_latency_secs(timestamp: f64, seconds: f64) -> f64 {
+  (timestamp + seconds).max(0.0)
+}
+
+// Called from JS
+#[tauri::command]
+pub async fn setlatency(seconds: f64, state: tauri::State<'_, LatencyOffset>) -> Result<(), String> {
+  if !seconds.is_finite() || seconds.abs() > MAX_LATENCY_SECONDS {
+    return Err(format!("latency offset must be between -{0} and {0} seconds, got {1}", MAX_LATENCY_SECONDS, seconds));
+  }
+  *state.inner.lock().await = seconds;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn latency_shifts_midi_offsets() {
+    assert_eq!(apply_latency_ms(100, 0.02), 120);
+    assert_eq!(apply_latency_ms(100, -0.02), 80);
+  }
+
+  #[test]
+  fn negative_latency_clamps_midi_offsets_at_zero() {
+    assert_eq!(apply_latency_ms(10, -0.02), 0);
+  }
+
+  #[test]
+  fn latency_shifts_osc_timestamps() {
+    assert!((apply_latency_secs(1000.0, 0.02) - 1000.02).abs() < 1e-9);
+    assert!((apply_latency_secs(1000.0, -0.02) - 999.98).abs() < 1e-9);
+  }
+
+  #[test]
+  fn negative_latency_clamps_osc_timestamps_at_zero() {
+    assert_eq!(apply_latency_secs(0.01, -0.02), 0.0);
+  }
+}
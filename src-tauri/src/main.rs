@@ -4,6 +4,8 @@
 mod midibridge;
 mod oscbridge;
 mod loggerbridge;
+mod latency;
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use loggerbridge::Logger;
@@ -26,13 +28,31 @@ fn main() {
     .manage(midibridge::AsyncInputTransmit {
       inner: Mutex::new(async_input_transmitter_midi),
     })
+    .manage(midibridge::MidiClockInput {
+      inner: Mutex::new(None),
+    })
     .manage(oscbridge::AsyncInputTransmit {
       inner: Mutex::new(async_input_transmitter_osc),
     })
-    .invoke_handler(tauri::generate_handler![midibridge::sendmidi, oscbridge::sendosc])
+    .manage(oscbridge::InvalidParamWarnings {
+      inner: Mutex::new(HashMap::new()),
+    })
+    .manage(latency::LatencyOffset {
+      inner: Mutex::new(0.0),
+    })
+    .invoke_handler(
+      tauri::generate_handler![
+        midibridge::sendmidi,
+        midibridge::listenmidiclock,
+        midibridge::stopmidiclock,
+        oscbridge::sendosc,
+        latency::setlatency
+      ]
+    )
     .setup(|app| {
       let window = Arc::new(app.get_window("main").unwrap());
       let logger = Logger { window };
+      app.manage(logger.clone());
       midibridge::init(
         logger.clone(),
         async_input_receiver_midi,
@@ -1,13 +1,14 @@
-use std::collections::HashMap;
+use std::collections::{ HashMap, VecDeque };
 use std::sync::Arc;
 use std::time::Duration;
-use midir::MidiOutput;
+use midir::{ MidiInput, MidiInputConnection, MidiOutput };
 
 use tokio::sync::{ mpsc, Mutex };
 use tokio::time::Instant;
 use serde::Deserialize;
 use std::thread::sleep;
 
+use crate::latency::{ apply_latency_ms, LatencyOffset };
 use crate::loggerbridge::Logger;
 pub struct MidiMessage {
   pub message: Vec<u8>,
@@ -20,6 +21,73 @@ pub struct AsyncInputTransmit {
   pub inner: Mutex<mpsc::Sender<Vec<MidiMessage>>>,
 }
 
+// dropping the sender closes the clock input port, see listenmidiclock
+pub struct MidiClockInput {
+  pub inner: Mutex<Option<std::sync::mpsc::Sender<()>>>,
+}
+
+const CLOCK_MESSAGE: u8 = 0xf8;
+const START_MESSAGE: u8 = 0xfa;
+const CONTINUE_MESSAGE: u8 = 0xfb;
+const STOP_MESSAGE: u8 = 0xfc;
+const CLOCK_PPQN: usize = 24;
+
+#[derive(Clone, serde::Serialize)]
+pub struct MidiClockPayload {
+  // "start", "continue", "stop" or "tempo"
+  pub message_type: String,
+  pub bpm: f64,
+}
+
+// derives the tempo of an external device from its 24 ppqn clock ticks
+#[derive(Default)]
+pub struct MidiClock {
+  last_tick: Option<u64>,
+  intervals: VecDeque<u64>,
+  // intervals measured since the last reported tempo, ticks after a pause don't count
+  since_tempo: usize,
+}
+
+impl MidiClock {
+  pub fn reset(&mut self) {
+    self.last_tick = None;
+    self.intervals.clear();
+    self.since_tempo = 0;
+  }
+
+  // keeps the measured tempo, but the gap until the next tick is not an interval
+  pub fn pause(&mut self) {
+    self.last_tick = None;
+  }
+
+  // average over the last quarter note, 0 until enough ticks came in
+  pub fn bpm(&self) -> f64 {
+    if self.intervals.is_empty() {
+      return 0.0;
+    }
+    let average_us = (self.intervals.iter().sum::<u64>() as f64) / (self.intervals.len() as f64);
+    if average_us <= 0.0 {
+      return 0.0;
+    }
+    60_000_000.0 / (average_us * (CLOCK_PPQN as f64))
+  }
+
+  // stamp in microseconds, returns the tempo once per quarter note
+  pub fn tick(&mut self, stamp: u64) -> Option<f64> {
+    let last_tick = self.last_tick.replace(stamp)?;
+    if self.intervals.len() == CLOCK_PPQN {
+      self.intervals.pop_front();
+    }
+    self.intervals.push_back(stamp.saturating_sub(last_tick));
+    self.since_tempo += 1;
+    if self.since_tempo >= CLOCK_PPQN && self.intervals.len() == CLOCK_PPQN {
+      self.since_tempo = 0;
+      return Some(self.bpm());
+    }
+    None
+  }
+}
+
 pub fn init(
   logger: Logger,
   async_input_receiver: mpsc::Receiver<Vec<MidiMessage>>,
@@ -119,6 +187,60 @@ pub fn init(
   });
 }
 
+// same matching as the outputs: the exact name, or else the first port containing it
+fn find_port(port_names: &[String], requestedport: &str) -> Option<usize> {
+  port_names
+    .iter()
+    .position(|port_name| port_name == requestedport)
+    .or_else(|| port_names.iter().position(|port_name| port_name.contains(requestedport)))
+}
+
+fn connect_clock(logger: &Logger, requestedport: &str) -> Result<MidiInputConnection<MidiClock>, String> {
+  let midiin = MidiInput::new("strudel clock").map_err(|e| e.to_string())?;
+  let ports = midiin.ports();
+  let port_names: Vec<String> = ports
+    .iter()
+    .map(|port| midiin.port_name(port).unwrap_or_default())
+    .collect();
+  let index = find_port(&port_names, requestedport).ok_or_else(|| {
+    format!("failed to find midi clock input: {}, available: {}", requestedport, port_names.join(", "))
+  })?;
+  let port_name = port_names[index].clone();
+  logger.log(format!("Following midi clock from {}", port_name), "".to_string());
+  let window = Arc::clone(&logger.window);
+  midiin
+    .connect(
+      &ports[index],
+      &port_name,
+      move |stamp, message, clock| {
+        let payload = match message.first() {
+          Some(&CLOCK_MESSAGE) => clock.tick(stamp).map(|bpm| ("tempo", bpm)),
+          Some(&START_MESSAGE) => {
+            clock.reset();
+            Some(("start", 0.0))
+          }
+          Some(&CONTINUE_MESSAGE) => {
+            clock.pause();
+            Some(("continue", clock.bpm()))
+          }
+          Some(&STOP_MESSAGE) => {
+            clock.pause();
+            Some(("stop", clock.bpm()))
+          }
+          _ => None,
+        };
+        if let Some((message_type, bpm)) = payload {
+          let _ = window.emit("midi-clock", MidiClockPayload {
+            message_type: message_type.to_string(),
+            bpm,
+          });
+        }
+      },
+      MidiClock::default()
+    )
+    .map_err(|e| e.to_string())
+}
+
 pub async fn async_process_model(
   mut input_reciever: mpsc::Receiver<Vec<MidiMessage>>,
   output_transmitter: mpsc::Sender<Vec<MidiMessage>>
@@ -139,16 +261,18 @@ pub struct MessageFromJS {
 #[tauri::command]
 pub async fn sendmidi(
   messagesfromjs: Vec<MessageFromJS>,
-  state: tauri::State<'_, AsyncInputTransmit>
+  state: tauri::State<'_, AsyncInputTransmit>,
+  latency: tauri::State<'_, LatencyOffset>
 ) -> Result<(), String> {
   let async_proc_input_tx = state.inner.lock().await;
+  let latency = *latency.inner.lock().await;
   let mut messages_to_process: Vec<MidiMessage> = Vec::new();
 
   for m in messagesfromjs {
     let message_to_process = MidiMessage {
       instant: Instant::now(),
       message: m.message,
-      offset: m.offset,
+      offset: apply_latency_ms(m.offset, latency),
       requestedport: m.requestedport,
     };
     messages_to_process.push(message_to_process);
@@ -156,3 +280,96 @@ pub async fn sendmidi(
 
   async_proc_input_tx.send(messages_to_process).await.map_err(|e| e.to_string())
 }
+
+// Called from JS, only one clock input is open at a time so a device sending
+// clock on several ports doesn't produce duplicate events
+#[tauri::command]
+pub async fn listenmidiclock(
+  requestedport: String,
+  logger: tauri::State<'_, Logger>,
+  state: tauri::State<'_, MidiClockInput>
+) -> Result<(), String> {
+  let mut stop_transmitter = state.inner.lock().await;
+  stop_transmitter.take();
+  let logger = logger.inner().clone();
+  let (result_transmitter, result_receiver) = tokio::sync::oneshot::channel();
+  let (new_stop_transmitter, stop_receiver) = std::sync::mpsc::channel::<()>();
+  // the connection has to stay on the thread that opened it, it stops listening when dropped
+  std::thread::spawn(move || {
+    match connect_clock(&logger, &requestedport) {
+      Ok(_connection) => {
+        let _ = result_transmitter.send(Ok(()));
+        // returns once the sender is dropped
+        let _ = stop_receiver.recv();
+      }
+      Err(err) => {
+        let _ = result_transmitter.send(Err(err));
+      }
+    }
+  });
+  result_receiver.await.map_err(|e| e.to_string())??;
+  *stop_transmitter = Some(new_stop_transmitter);
+  Ok(())
+}
+
+// Called from JS
+#[tauri::command]
+pub async fn stopmidiclock(state: tauri::State<'_, MidiClockInput>) -> Result<(), String> {
+  state.inner.lock().await.take();
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // 120 bpm at 24 ppqn
+  const TICK_US: u64 = 20_833;
+
+  fn feed(clock: &mut MidiClock, start: u64, ticks: u64) -> Vec<Option<f64>> {
+    (0..ticks).map(|i| clock.tick(start + i * TICK_US)).collect()
+  }
+
+  #[test]
+  fn find_port_prefers_exact_names() {
+    let port_names = vec!["IAC Driver Bus 1".to_string(), "Bus 1".to_string()];
+    assert_eq!(find_port(&port_names, "Bus 1"), Some(1));
+    assert_eq!(find_port(&port_names, "IAC"), Some(0));
+    assert_eq!(find_port(&port_names, "Bus 2"), None);
+  }
+
+  #[test]
+  fn clock_ticks_derive_the_tempo() {
+    let mut clock = MidiClock::default();
+    let tempos = feed(&mut clock, 0, 48);
+    // the first tempo is ready one quarter note (24 intervals) after the first tick
+    assert!(tempos[..24].iter().all(|tempo| tempo.is_none()));
+    let bpm = tempos[24].expect("tempo after one quarter note");
+    assert!((bpm - 120.0).abs() < 0.01, "got {}", bpm);
+    assert!(tempos[25..].iter().all(|tempo| tempo.is_none()));
+  }
+
+  #[test]
+  fn reset_forgets_the_tempo() {
+    let mut clock = MidiClock::default();
+    feed(&mut clock, 0, 48);
+    clock.reset();
+    assert_eq!(clock.bpm(), 0.0);
+    let tempos = feed(&mut clock, 10_000_000, 25);
+    assert!(tempos[..24].iter().all(|tempo| tempo.is_none()));
+    assert!(tempos[24].is_some());
+  }
+
+  #[test]
+  fn pause_does_not_count_the_gap_as_an_interval() {
+    let mut clock = MidiClock::default();
+    let last_stamp = 24 * TICK_US;
+    feed(&mut clock, 0, 25);
+    clock.pause();
+    assert!((clock.bpm() - 120.0).abs() < 0.01);
+    // continue after a 5 second pause
+    let tempos = feed(&mut clock, last_stamp + 5_000_000, 25);
+    let bpm = tempos[24].expect("tempo after one quarter note");
+    assert!((bpm - 120.0).abs() < 0.01, "got {}", bpm);
+  }
+}
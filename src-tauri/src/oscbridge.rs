@@ -4,11 +4,13 @@ use rosc::{OscBundle, OscMessage, OscPacket, OscType};
 use std::net::UdpSocket;
 
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::thread::sleep;
-use std::time::{Duration};
+use std::time::{Duration, Instant};
 use tokio::sync::{mpsc, Mutex};
 
+use crate::latency::{apply_latency_secs, LatencyOffset};
 use crate::loggerbridge::Logger;
 pub struct OscMsg {
     pub msg_buf: Vec<u8>,
@@ -23,6 +25,8 @@ const UNIX_OFFSET: u64 = 2_208_988_800; // 70 years in seconds
 const TWO_POW_32: f64 = (u32::MAX as f64) + 1.0; // Number of bits in a `u32`
 const NANOS_PER_SECOND: f64 = 1.0e9;
 const SECONDS_PER_NANO: f64 = 1.0 / NANOS_PER_SECOND;
+// patterns repeat an invalid param on every event, warn about it at most this often
+const INVALID_PARAM_WARNING_INTERVAL: Duration = Duration::from_secs(5);
 
 pub fn init(
     logger: Logger,
@@ -97,6 +101,11 @@ pub async fn async_process_model(
     Ok(())
 }
 
+// when we last warned about each invalid param, see sendosc
+pub struct InvalidParamWarnings {
+    pub inner: Mutex<HashMap<String, Instant>>,
+}
+
 #[derive(Deserialize)]
 pub struct Param {
     name: String,
@@ -109,27 +118,88 @@ pub struct MessageFromJS {
     timestamp: f64,
     target: String,
 }
+// NaN/Infinity from the pattern (or out of f32 range) would otherwise be sent as is
+fn parse_finite_number(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(number) if number.is_finite() => Ok(number),
+        _ => Err(format!("invalid number: {}", value)),
+    }
+}
+
+// invalid numeric params are left out entirely and returned as (name, value),
+// sending a placeholder like 0 would silence params such as speed or gain
+fn params_to_args(params: Vec<Param>) -> (Vec<OscType>, Vec<(String, String)>) {
+    let mut args = Vec::new();
+    let mut invalid_params = Vec::new();
+    for p in params {
+        let value = if p.valueisnumber {
+            match parse_finite_number(&p.value) {
+                Ok(number) => OscType::Float(number),
+                Err(_) => {
+                    invalid_params.push((p.name, p.value));
+                    continue;
+                }
+            }
+        } else {
+            OscType::String(p.value)
+        };
+        args.push(OscType::String(p.name));
+        args.push(value);
+    }
+    (args, invalid_params)
+}
+
+// warn at most once per INVALID_PARAM_WARNING_INTERVAL for each param
+fn invalid_param_warnings(
+    warned_params: &mut HashMap<String, Instant>,
+    invalid_params: Vec<(String, String)>,
+    now: Instant,
+) -> Vec<String> {
+    invalid_params
+        .into_iter()
+        .filter(|(name, _)| {
+            let warned_recently = match warned_params.get(name) {
+                Some(last_warning) => {
+                    now.duration_since(*last_warning) < INVALID_PARAM_WARNING_INTERVAL
+                }
+                None => false,
+            };
+            if !warned_recently {
+                warned_params.insert(name.clone(), now);
+            }
+            !warned_recently
+        })
+        .map(|(name, value)| {
+            format!(
+                "OSC param {} has invalid value {}, leaving it out so the receiver uses its default",
+                name, value
+            )
+        })
+        .collect()
+}
+
 // Called from JS
 #[tauri::command]
 pub async fn sendosc(
     messagesfromjs: Vec<MessageFromJS>,
     state: tauri::State<'_, AsyncInputTransmit>,
+    logger: tauri::State<'_, Logger>,
+    warnings: tauri::State<'_, InvalidParamWarnings>,
+    latency: tauri::State<'_, LatencyOffset>,
 ) -> Result<(), String> {
     let async_proc_input_tx = state.inner.lock().await;
+    let latency = *latency.inner.lock().await;
     let mut messages_to_process: Vec<OscMsg> = Vec::new();
     for m in messagesfromjs {
-        let mut args = Vec::new();
-        for p in m.params {
-            args.push(OscType::String(p.name));
-            if p.valueisnumber {
-                args.push(OscType::Float(p.value.parse().unwrap()));
-            } else {
-                args.push(OscType::String(p.value));
-            }
+        let (args, invalid_params) = params_to_args(m.params);
+        let mut warned_params = warnings.inner.lock().await;
+        for warning in invalid_param_warnings(&mut warned_params, invalid_params, Instant::now()) {
+            logger.log(warning, "error".to_string());
         }
         // let start = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
 
-        let time_delay = Duration::from_secs_f64(m.timestamp);
+        let timestamp = apply_latency_secs(m.timestamp, latency);
+        let time_delay = Duration::from_secs_f64(timestamp);
         let duration_since_epoch =
         time_delay + Duration::new(UNIX_OFFSET, 0);
 
@@ -153,10 +223,7 @@ pub async fn sendosc(
 
         let msg_buf = encoder::encode(&OscPacket::Bundle(bundle)).unwrap();
 
-        let message_to_process = OscMsg {
-            msg_buf,
-            timestamp: m.timestamp,
-        };
+        let message_to_process = OscMsg { msg_buf, timestamp };
         messages_to_process.push(message_to_process);
     }
 
@@ -165,3 +232,89 @@ pub async fn sendosc(
         .await
         .map_err(|e| e.to_string())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn param(name: &str, value: &str, valueisnumber: bool) -> Param {
+        Param {
+            name: name.to_string(),
+            value: value.to_string(),
+            valueisnumber,
+        }
+    }
+
+    #[test]
+    fn parse_finite_number_accepts_finite_values() {
+        assert_eq!(parse_finite_number("0.5"), Ok(0.5));
+        assert_eq!(parse_finite_number("-2"), Ok(-2.0));
+    }
+
+    #[test]
+    fn parse_finite_number_rejects_non_finite_values() {
+        for value in ["NaN", "inf", "-inf", "abc", "1e39"] {
+            assert!(
+                parse_finite_number(value).is_err(),
+                "{} should be rejected",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn params_to_args_leaves_out_invalid_numbers() {
+        let (args, invalid_params) = params_to_args(vec![
+            param("s", "bd", false),
+            param("speed", "NaN", true),
+            param("gain", "0.8", true),
+        ]);
+        assert_eq!(
+            args,
+            vec![
+                OscType::String("s".to_string()),
+                OscType::String("bd".to_string()),
+                OscType::String("gain".to_string()),
+                OscType::Float(0.8),
+            ]
+        );
+        assert_eq!(
+            invalid_params,
+            vec![("speed".to_string(), "NaN".to_string())]
+        );
+    }
+
+    #[test]
+    fn invalid_param_warnings_are_rate_limited_per_param() {
+        let mut warned_params = HashMap::new();
+        let start = Instant::now();
+        let (_, invalid_params) = params_to_args(vec![param("speed", "NaN", true)]);
+        let warnings = invalid_param_warnings(&mut warned_params, invalid_params, start);
+        assert_eq!(
+            warnings,
+            vec!["OSC param speed has invalid value NaN, leaving it out so the receiver uses its default"]
+        );
+
+        let (_, invalid_params) = params_to_args(vec![
+            param("speed", "NaN", true),
+            param("cutoff", "inf", true),
+        ]);
+        let warnings = invalid_param_warnings(
+            &mut warned_params,
+            invalid_params,
+            start + Duration::from_secs(1),
+        );
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("OSC param cutoff"));
+
+        // the same param warns again once the interval has passed
+        let (_, invalid_params) = params_to_args(vec![param("speed", "NaN", true)]);
+        let warnings = invalid_param_warnings(
+            &mut warned_params,
+            invalid_params,
+            start + INVALID_PARAM_WARNING_INTERVAL,
+        );
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("OSC param speed"));
+    }
+}